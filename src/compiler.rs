@@ -0,0 +1,164 @@
+use super::value::Value;
+use super::Op;
+
+#[derive(Debug, Clone, Copy)]
+pub enum BinKind {
+    Add,
+    Div,
+    Eq,
+    Exp,
+    Gt,
+    Lt,
+    Mul,
+    Ne,
+    Sub,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UnKind {
+    Double,
+    Fact,
+    Inv,
+    Square,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DrainKind {
+    Prod,
+    Sum,
+}
+
+#[derive(Debug, Clone)]
+pub enum Instr {
+    BinOp(BinKind),
+    CallWord(String),
+    Clear,
+    Drain(DrainKind),
+    Get(String),
+    Jump(usize),
+    JumpIfZero(usize),
+    NumPush(Value),
+    Set(String),
+    Swap,
+    UnOp(UnKind),
+}
+
+/// Lowers a sequence of parsed `Op`s into a flat instruction stream.
+/// `if`/`while` combinators become forward/backward jumps, backpatched
+/// once their target offsets are known.
+pub fn compile(ops: Vec<Op>) -> Vec<Instr> {
+    let mut code = Vec::new();
+
+    for op in ops {
+        compile_op(op, &mut code);
+    }
+
+    code
+}
+
+fn compile_op(op: Op, code: &mut Vec<Instr>) {
+    match op {
+        Op::Add => code.push(Instr::BinOp(BinKind::Add)),
+        Op::Div => code.push(Instr::BinOp(BinKind::Div)),
+        Op::Eq => code.push(Instr::BinOp(BinKind::Eq)),
+        Op::Exp => code.push(Instr::BinOp(BinKind::Exp)),
+        Op::Gt => code.push(Instr::BinOp(BinKind::Gt)),
+        Op::Lt => code.push(Instr::BinOp(BinKind::Lt)),
+        Op::Mul => code.push(Instr::BinOp(BinKind::Mul)),
+        Op::Ne => code.push(Instr::BinOp(BinKind::Ne)),
+        Op::Sub => code.push(Instr::BinOp(BinKind::Sub)),
+
+        Op::Double => code.push(Instr::UnOp(UnKind::Double)),
+        Op::Fact => code.push(Instr::UnOp(UnKind::Fact)),
+        Op::Inv => code.push(Instr::UnOp(UnKind::Inv)),
+        Op::Square => code.push(Instr::UnOp(UnKind::Square)),
+
+        Op::Sum => code.push(Instr::Drain(DrainKind::Sum)),
+        Op::Prod => code.push(Instr::Drain(DrainKind::Prod)),
+
+        Op::Clear => code.push(Instr::Clear),
+        Op::Swap => code.push(Instr::Swap),
+        Op::Push(value) => code.push(Instr::NumPush(value)),
+        Op::VarInit(name) => code.push(Instr::Set(name)),
+        Op::VarRef(name) => code.push(Instr::Get(name)),
+        Op::Call(name) => code.push(Instr::CallWord(name)),
+
+        Op::If(name) => {
+            let jump_if_zero = code.len();
+            code.push(Instr::JumpIfZero(0));
+            code.push(Instr::CallWord(name));
+            backpatch(code, jump_if_zero);
+        }
+        Op::While(cond_name, body_name) => {
+            let loop_start = code.len();
+            code.push(Instr::CallWord(cond_name));
+
+            let jump_if_zero = code.len();
+            code.push(Instr::JumpIfZero(0));
+            code.push(Instr::CallWord(body_name));
+            code.push(Instr::Jump(loop_start));
+
+            backpatch(code, jump_if_zero);
+        }
+
+        // Captured by `eval`'s definition handling before a token
+        // stream ever reaches the compiler.
+        Op::DefStart | Op::DefEnd => {}
+    }
+}
+
+fn backpatch(code: &mut [Instr], jump_if_zero: usize) {
+    code[jump_if_zero] = Instr::JumpIfZero(code.len());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::vm;
+    use super::super::State;
+    use std::rc::Rc;
+
+    #[test]
+    fn compile_simple_arithmetic() {
+        let code = compile(vec![Op::Push(Value::Int(3)), Op::Push(Value::Int(5)), Op::Add]);
+        let mut state = State::new();
+
+        vm::run(&mut state, &code).unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(8)));
+    }
+
+    #[test]
+    fn compile_while_loop() {
+        let mut state = State::new();
+
+        vm::run(
+            &mut state,
+            &compile(vec![Op::Push(Value::Int(0)), Op::VarInit("n".to_string())]),
+        )
+        .unwrap();
+
+        Rc::make_mut(&mut state.words).insert(
+            "cond".to_string(),
+            compile(vec![
+                Op::VarRef("n".to_string()),
+                Op::Push(Value::Int(3)),
+                Op::Lt,
+            ]),
+        );
+        Rc::make_mut(&mut state.words).insert(
+            "incr".to_string(),
+            compile(vec![
+                Op::VarRef("n".to_string()),
+                Op::Push(Value::Int(1)),
+                Op::Add,
+                Op::VarInit("n".to_string()),
+            ]),
+        );
+
+        let code = compile(vec![Op::While("cond".to_string(), "incr".to_string())]);
+        vm::run(&mut state, &code).unwrap();
+
+        vm::run(&mut state, &compile(vec![Op::VarRef("n".to_string())])).unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(3)));
+    }
+}
@@ -0,0 +1,142 @@
+use ansi_term::Color::{Cyan, Green, Purple, Red};
+use ansi_term::Style;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Helper};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use super::lex::{self, TokenData};
+use super::State;
+
+/// Bundles syntax highlighting, inline result hints, word/variable
+/// completion, and `: ... ;` continuation into a single rustyline
+/// `Helper`, keeping these UI concerns out of `State` itself.
+pub struct RclacHelper {
+    state: State,
+    hint_cache: RefCell<Option<(String, String)>>,
+}
+
+impl RclacHelper {
+    pub fn new(state: State) -> RclacHelper {
+        RclacHelper {
+            state,
+            hint_cache: RefCell::new(None),
+        }
+    }
+}
+
+impl Completer for RclacHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_whitespace())
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+
+        let candidates = lex::OPS
+            .iter()
+            .map(|op| op.to_string())
+            .chain(self.state.var_names().map(|name| format!("${}", name)))
+            .chain(self.state.word_names().map(str::to_string))
+            .filter(|candidate| candidate.starts_with(prefix))
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: candidate,
+            })
+            .collect();
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for RclacHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context) -> Option<String> {
+        if pos != line.len() || line.trim().is_empty() {
+            return None;
+        }
+
+        if let Some((cached_line, hint)) = self.hint_cache.borrow().as_ref() {
+            if cached_line == line {
+                return Some(hint.clone());
+            }
+        }
+
+        // Previewing runs `line` against a disposable copy of `state`,
+        // since `eval` mutates in place. `snapshot` clones only the
+        // (typically tiny) stack and vars and shares the words table via
+        // `Rc`, so previewing a keystroke never deep-clones the whole
+        // interpreter state; the cache above spares repeated renders of
+        // the same line from even that.
+        let preview = match self.state.snapshot().eval(line) {
+            Ok(state) => format!("{}", state),
+            Err(diags) => diags
+                .iter()
+                .map(|diag| diag.message.clone())
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+
+        let hint = format!(" => {}", preview);
+        *self.hint_cache.borrow_mut() = Some((line.to_string(), hint.clone()));
+        Some(hint)
+    }
+}
+
+impl Highlighter for RclacHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let mut out = String::with_capacity(line.len());
+        let mut last_end = 0;
+
+        for token in lex::lex(line) {
+            out.push_str(&line[last_end..token.span.start]);
+
+            let text = &line[token.span.start..token.span.end];
+            let painted = match token.data {
+                TokenData::Number(_) => Cyan.paint(text).to_string(),
+                TokenData::Op(_) => Green.paint(text).to_string(),
+                TokenData::VarInit(_) | TokenData::VarRef(_) => Purple.paint(text).to_string(),
+                TokenData::Unknown(_) => Red.paint(text).to_string(),
+            };
+            out.push_str(&painted);
+
+            last_end = token.span.end;
+        }
+        out.push_str(&line[last_end..]);
+
+        Cow::Owned(out)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(Style::new().dimmed().paint(hint).to_string())
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+impl Validator for RclacHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let depth = lex::lex(ctx.input())
+            .iter()
+            .fold(0i32, |depth, token| match &token.data {
+                TokenData::Op(op) if op == ":" => depth + 1,
+                TokenData::Op(op) if op == ";" => depth - 1,
+                _ => depth,
+            });
+
+        if depth > 0 {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+impl Helper for RclacHelper {}
@@ -0,0 +1,165 @@
+use regex::Regex;
+
+pub(crate) static OPS: &[&str] = &[
+    "*", "**", "+", "/", "-", "!", "^", "^^", "c", "inv", "swap", "sum", "prod", ":", ";", "<",
+    ">", "==", "!=", "if", "while",
+];
+
+lazy_static! {
+    static ref INIT_RE: Regex = Regex::new(r"^=([a-zA-Z][a-zA-Z0-9]*)$").unwrap();
+    static ref VAR_RE: Regex = Regex::new(r"^\$([a-zA-Z][a-zA-Z0-9]*)$").unwrap();
+}
+
+/// A half-open byte range into the original input line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenData {
+    Number(String),
+    Op(String),
+    VarInit(String),
+    VarRef(String),
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub data: TokenData,
+    pub span: Span,
+}
+
+/// A diagnostic produced while lexing or evaluating a line, with enough
+/// span information for the REPL to print a caret under the offending
+/// token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diag {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diag {
+    pub fn new(message: impl Into<String>, span: Span) -> Diag {
+        Diag {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+/// Splits `input` on whitespace into spanned, classified tokens.
+pub fn lex(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut end = start;
+        while let Some(&(idx, c)) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            end = idx + c.len_utf8();
+            chars.next();
+        }
+
+        let text = &input[start..end];
+        tokens.push(Token {
+            data: classify(text),
+            span: Span::new(start, end),
+        });
+    }
+
+    tokens
+}
+
+fn classify(text: &str) -> TokenData {
+    if OPS.contains(&text) {
+        TokenData::Op(text.to_string())
+    } else if looks_numeric(text) {
+        TokenData::Number(text.to_string())
+    } else if let Some(name) = INIT_RE.captures(text).and_then(|c| c.get(1)) {
+        TokenData::VarInit(name.as_str().to_string())
+    } else if let Some(name) = VAR_RE.captures(text).and_then(|c| c.get(1)) {
+        TokenData::VarRef(name.as_str().to_string())
+    } else {
+        TokenData::Unknown(text.to_string())
+    }
+}
+
+/// Loose shape check for a number token (`int`, `float`, or an
+/// `a+bi`-style complex literal); the actual parse, and any reporting of
+/// a malformed literal, happens downstream in `value::parse`.
+fn looks_numeric(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_digit() => {}
+        Some('+') | Some('-') | Some('.') if text.len() > 1 => {}
+        _ => return false,
+    }
+
+    text.chars().all(|c| c.is_ascii_digit() || "+-.i".contains(c))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lex_spans() {
+        let tokens = lex("3 5 +");
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token {
+                    data: TokenData::Number("3".to_string()),
+                    span: Span::new(0, 1),
+                },
+                Token {
+                    data: TokenData::Number("5".to_string()),
+                    span: Span::new(2, 3),
+                },
+                Token {
+                    data: TokenData::Op("+".to_string()),
+                    span: Span::new(4, 5),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_variables() {
+        let tokens = lex("3 =foo $foo");
+
+        assert_eq!(
+            tokens[1].data,
+            TokenData::VarInit("foo".to_string())
+        );
+        assert_eq!(tokens[2].data, TokenData::VarRef("foo".to_string()));
+    }
+
+    #[test]
+    fn lex_unknown() {
+        let tokens = lex("wat");
+
+        assert_eq!(tokens[0].data, TokenData::Unknown("wat".to_string()));
+    }
+}
@@ -1,47 +1,70 @@
 extern crate ansi_term;
 #[macro_use]
 extern crate lazy_static;
+extern crate num_complex;
 extern crate regex;
 extern crate rustyline;
 
-use ansi_term::Color::{Green, Yellow};
-use regex::Regex;
-use rustyline::completion::Completer;
-use rustyline::{Config, Editor};
+mod compiler;
+mod helper;
+mod lex;
+mod value;
+mod vm;
+
+use ansi_term::Color::{Green, Red};
+use compiler::Instr;
+use helper::RclacHelper;
+use lex::{Diag, Token, TokenData};
+use rustyline::{CompletionType, Config, Editor};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use std::str::FromStr;
+use std::rc::Rc;
 use std::vec::Drain;
+use value::Value;
 
 static PROMPT: &str = ">> ";
 
 fn main() {
     let mut state = State::new();
 
-    let config = Config::builder().tab_completion(false).build();
-    let mut editor = Editor::<State>::with_config(config);
+    let config = Config::builder().completion_type(CompletionType::List).build();
+    let mut editor = Editor::<RclacHelper>::with_config(config);
 
     loop {
-        editor.set_completer(Some(state.to_owned()));
+        editor.set_helper(Some(RclacHelper::new(state.clone())));
 
         match editor.readline(PROMPT) {
-            Ok(line) => {
-                state.eval(&line);
-            }
+            Ok(line) => match state.eval(&line) {
+                Ok(state) => {
+                    let top = state
+                        .peek()
+                        .map(|value| format!("{}", value))
+                        .unwrap_or_else(|| "0".to_string());
+                    println!("= {}", Green.paint(top));
+                }
+                Err(diags) => {
+                    for diag in diags {
+                        println!("{}", Red.paint(format!("error: {}", diag.message)));
+                        println!(
+                            "   {}{}",
+                            " ".repeat(diag.span.start),
+                            "^".repeat(diag.span.len().max(1))
+                        );
+                    }
+                }
+            },
             Err(_) => break,
         }
-
-        println!(
-            "= {}",
-            Green.paint(format!("{}", state.peek().unwrap_or(&0)))
-        );
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 struct State {
-    stack: Vec<isize>,
-    vars: HashMap<String, isize>,
+    stack: Vec<Value>,
+    vars: HashMap<String, Value>,
+    words: Rc<HashMap<String, Vec<Instr>>>,
+    call_depth: usize,
 }
 
 impl State {
@@ -49,108 +72,239 @@ impl State {
         State {
             stack: Vec::with_capacity(0xFF),
             vars: HashMap::new(),
+            words: Rc::new(HashMap::new()),
+            call_depth: 0,
         }
     }
 
-    pub fn exec(&mut self, op: Op) {
+    /// Compiles a single parsed `Op` to bytecode and runs it against this
+    /// state. `:`/`;` are handled earlier by `eval`'s capture logic and
+    /// should never reach here.
+    pub fn exec(&mut self, op: Op) -> Result<(), String> {
         match op {
-            Op::Add => {
-                self.apply2(|a, b| a + b);
-            }
-            Op::Clear => {
-                self.clear();
-            }
-            Op::Div => {
-                self.apply2(|a, b| save_div(b, a).unwrap_or(0));
-            }
-            Op::Double => {
-                self.apply(|a| a * 2);
+            Op::DefStart => Err("unexpected `:`".to_string()),
+            Op::DefEnd => Err("unexpected `;`".to_string()),
+            op => {
+                let code = compiler::compile(vec![op]);
+                vm::run(self, &code)
             }
-            Op::Exp => {
-                self.apply2(|a, b| b.pow(a as u32));
-            }
-            Op::Fact => {
-                self.apply(|a| (1..a + 1).product());
-            }
-            Op::Square => {
-                self.apply(|a| a.pow(2));
-            }
-            Op::Sub => {
-                self.apply2(|a, b| b - a);
-            }
-            Op::Mul => {
-                self.apply2(|a, b| a * b);
-            }
-            Op::Inv => {
-                self.apply(|a| -a);
-            }
-            Op::Prod => {
-                let product = self.drain().product();
-                self.push(product);
+        }
+    }
+
+    /// A cheap copy for speculative evaluation (e.g. previewing a line as
+    /// the user types it): the stack and vars are cloned since they're
+    /// typically tiny, while the words table is shared via `Rc` rather
+    /// than deep-cloned. A definition captured against the copy triggers
+    /// a copy-on-write of the words table, leaving the original intact.
+    fn snapshot(&self) -> State {
+        State {
+            stack: self.stack.clone(),
+            vars: self.vars.clone(),
+            words: Rc::clone(&self.words),
+            call_depth: 0,
+        }
+    }
+
+    /// Parses the whole line into a sequence of `Op`s, then compiles and
+    /// runs it as a single instruction stream. Parse-time diagnostics
+    /// (unknown tokens, malformed definitions) are collected across the
+    /// whole line before anything runs; a runtime error aborts the run
+    /// and is reported against the line as a whole.
+    pub fn eval(&mut self, cmds: &str) -> Result<&mut Self, Vec<Diag>> {
+        let all_tokens = lex::lex(cmds);
+        if all_tokens.is_empty() {
+            return Ok(self);
+        }
+
+        let line_span = lex::Span::new(
+            all_tokens[0].span.start,
+            all_tokens[all_tokens.len() - 1].span.end,
+        );
+
+        let mut tokens = all_tokens.into_iter();
+        let mut ops = Vec::new();
+        let mut diags = Vec::new();
+
+        while let Some(token) = tokens.next() {
+            if token.data == TokenData::Op(":".to_string()) {
+                if let Err((message, span)) = self.capture_def(&mut tokens) {
+                    diags.push(Diag::new(message, span));
+                }
+                continue;
             }
-            Op::Push(value) => {
-                self.push(value);
+
+            match self.parse_op_from_tokens(token, &mut tokens) {
+                Ok(op) => ops.push(op),
+                Err((message, span)) => diags.push(Diag::new(message, span)),
             }
-            Op::Sum => {
-                let sum = self.drain().sum();
-                self.push(sum);
+        }
+
+        if !diags.is_empty() {
+            return Err(diags);
+        }
+
+        let code = compiler::compile(ops);
+        match vm::run(self, &code) {
+            Ok(()) => Ok(self),
+            Err(message) => Err(vec![Diag::new(message, line_span)]),
+        }
+    }
+
+    /// Resolves a token to an `Op`, treating an otherwise-unknown token
+    /// as a call to a previously defined word.
+    fn resolve_op(&self, token: &Token) -> Result<Op, String> {
+        if let TokenData::Unknown(name) = &token.data {
+            if self.words.contains_key(name) {
+                return Ok(Op::Call(name.clone()));
             }
-            Op::Swap => {
-                if let Some((a, b)) = self.pop2() {
-                    self.push(a).push(b);
-                }
+        }
+
+        parse_op(token)
+    }
+
+    /// Resolves a single non-`:` token to an `Op`, reading the following
+    /// word name(s) for `if`/`while` rather than executing them
+    /// immediately. Shared by `eval`'s top-level loop and `capture_def`
+    /// so combinators work the same inside a word body.
+    fn parse_op_from_tokens(
+        &self,
+        token: Token,
+        tokens: &mut impl Iterator<Item = Token>,
+    ) -> Result<Op, (String, lex::Span)> {
+        if token.data == TokenData::Op("if".to_string()) {
+            return read_word_name(tokens, "if")
+                .map(Op::If)
+                .map_err(|message| (message, token.span));
+        }
+
+        if token.data == TokenData::Op("while".to_string()) {
+            return read_word_name(tokens, "while")
+                .and_then(|cond| read_word_name(tokens, "while").map(|body| Op::While(cond, body)))
+                .map_err(|message| (message, token.span));
+        }
+
+        match self.resolve_op(&token) {
+            Ok(Op::DefStart) => Err(("unexpected `:`".to_string(), token.span)),
+            Ok(Op::DefEnd) => Err(("unexpected `;`".to_string(), token.span)),
+            result => result.map_err(|message| (message, token.span)),
+        }
+    }
+
+    /// Captures the tokens between `:` and `;` as a named word body
+    /// rather than executing them. A placeholder is registered before
+    /// the body is parsed so that it can call itself recursively; on a
+    /// parse failure the word is rolled back to whatever it was before.
+    fn capture_def(
+        &mut self,
+        tokens: &mut impl Iterator<Item = Token>,
+    ) -> Result<(), (String, lex::Span)> {
+        let name_token = tokens
+            .next()
+            .ok_or_else(|| ("expected a word name after `:`".to_string(), lex::Span::new(0, 0)))?;
+
+        let name = match &name_token.data {
+            TokenData::Unknown(name) => name.clone(),
+            _ => {
+                return Err((
+                    "expected a word name after `:`".to_string(),
+                    name_token.span,
+                ))
             }
-            Op::VarInit(name) => {
-                if let Some(a) = self.stack.pop() {
-                    self.add_var(name, a);
-                }
+        };
+
+        let previous = Rc::make_mut(&mut self.words).insert(name.clone(), Vec::new());
+        let body = self.parse_def_body(&name, &name_token.span, tokens);
+
+        match body {
+            Ok(body) => {
+                Rc::make_mut(&mut self.words).insert(name, compiler::compile(body));
+                Ok(())
             }
-            Op::VarRef(name) => {
-                if let Some(a) = self.get_var(&name) {
-                    self.push(a);
+            Err(err) => {
+                let words = Rc::make_mut(&mut self.words);
+                match previous {
+                    Some(old) => {
+                        words.insert(name, old);
+                    }
+                    None => {
+                        words.remove(&name);
+                    }
                 }
+                Err(err)
             }
-            Op::Noop => {}
         }
     }
 
-    pub fn eval(&mut self, cmds: &str) -> &mut Self {
-        for token in cmds.split_whitespace() {
-            self.exec(token.into())
+    /// Parses the tokens up to (and consuming) the closing `;` into a
+    /// word body, resolving `if`/`while` and self-/mutually-recursive
+    /// word calls along the way.
+    fn parse_def_body(
+        &self,
+        name: &str,
+        name_span: &lex::Span,
+        tokens: &mut impl Iterator<Item = Token>,
+    ) -> Result<Vec<Op>, (String, lex::Span)> {
+        let mut body = Vec::new();
+        loop {
+            let token = tokens.next().ok_or_else(|| {
+                (
+                    format!("unterminated definition `{}`", name),
+                    name_span.clone(),
+                )
+            })?;
+
+            if token.data == TokenData::Op(";".to_string()) {
+                return Ok(body);
+            }
+
+            body.push(self.parse_op_from_tokens(token, tokens)?);
         }
-        self
     }
 
-    pub fn peek(&self) -> Option<&isize> {
+    pub fn peek(&self) -> Option<&Value> {
         self.stack.last()
     }
 
+    pub fn var_names(&self) -> impl Iterator<Item = &str> {
+        self.vars.keys().map(String::as_str)
+    }
+
+    pub fn word_names(&self) -> impl Iterator<Item = &str> {
+        self.words.keys().map(String::as_str)
+    }
+
     fn clear(&mut self) {
         self.stack.clear();
     }
 
-    fn drain(&mut self) -> Drain<isize> {
+    fn drain(&mut self) -> Drain<Value> {
         self.stack.drain(..)
     }
 
-    fn push(&mut self, val: isize) -> &mut Self {
+    fn push(&mut self, val: Value) -> &mut Self {
         self.stack.push(val);
         self
     }
 
-    fn apply(&mut self, func: impl FnOnce(isize) -> isize) {
-        if let Some(val) = self.stack.pop().map(func) {
-            self.push(val);
-        }
+    fn apply(&mut self, func: impl FnOnce(Value) -> Value) -> Result<(), String> {
+        let val = self.stack.pop().ok_or_else(underflow1)?;
+        self.push(func(val));
+        Ok(())
     }
 
-    fn apply2(&mut self, func: impl FnOnce(isize, isize) -> isize) {
-        if let Some((a, b)) = self.pop2() {
-            self.stack.push(func(a, b));
-        }
+    fn apply_try(&mut self, func: impl FnOnce(Value) -> Result<Value, String>) -> Result<(), String> {
+        let val = self.stack.pop().ok_or_else(underflow1)?;
+        self.push(func(val)?);
+        Ok(())
+    }
+
+    fn apply2(&mut self, func: impl FnOnce(Value, Value) -> Value) -> Result<(), String> {
+        let (a, b) = self.pop2().ok_or_else(underflow2)?;
+        self.stack.push(func(a, b));
+        Ok(())
     }
 
-    fn pop2(&mut self) -> Option<(isize, isize)> {
+    fn pop2(&mut self) -> Option<(Value, Value)> {
         if self.stack.len() > 1 {
             Some((self.stack.pop().unwrap(), self.stack.pop().unwrap()))
         } else {
@@ -158,11 +312,18 @@ impl State {
         }
     }
 
-    fn add_var(&mut self, key: String, value: isize) {
+    fn compare(&mut self, matches: impl FnOnce(Ordering) -> bool) -> Result<(), String> {
+        let (a, b) = self.pop2().ok_or_else(underflow2)?;
+        let ordering = value::compare(b, a)?;
+        self.push(bool_value(matches(ordering)));
+        Ok(())
+    }
+
+    fn add_var(&mut self, key: String, value: Value) {
         self.vars.insert(key, value);
     }
 
-    fn get_var(&self, key: &String) -> Option<isize> {
+    fn get_var(&self, key: &String) -> Option<Value> {
         self.vars.get(key).cloned()
     }
 }
@@ -172,97 +333,98 @@ impl Display for State {
         for val in self.stack.iter() {
             write!(f, "{} ", val)?
         }
-        Ok(())
-    }
-}
 
-impl Completer for State {
-    fn complete(&self, line: &str, _: usize) -> rustyline::Result<(usize, Vec<String>)> {
-        let state_display = Yellow.paint(format!("{}", self.to_owned().eval(line)));
-        Ok((0, vec![format!("{}", state_display)]))
+        if !self.words.is_empty() {
+            let mut names: Vec<&str> = self.words.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            write!(f, "| words: {}", names.join(" "))?;
+        }
+
+        Ok(())
     }
 }
 
+#[derive(Clone)]
 enum Op {
     Add,
+    Call(String),
     Clear,
+    DefEnd,
+    DefStart,
     Div,
     Double,
+    Eq,
     Exp,
     Fact,
+    Gt,
+    If(String),
     Inv,
+    Lt,
     Mul,
-    Noop,
+    Ne,
     Prod,
-    Push(isize),
+    Push(Value),
     Square,
     Sub,
     Sum,
     Swap,
     VarInit(String),
     VarRef(String),
+    While(String, String),
 }
 
-impl<'a> From<&'a str> for Op {
-    fn from(string: &str) -> Self {
-        match string {
-            "*" => Op::Mul,
-            "**" => Op::Double,
-            "+" => Op::Add,
-            "/" => Op::Div,
-            "-" => Op::Sub,
-            "!" => Op::Fact,
-            "^" => Op::Exp,
-            "^^" => Op::Square,
-            "c" => Op::Clear,
-            "inv" => Op::Inv,
-            "swap" => Op::Swap,
-            "sum" => Op::Sum,
-            "prod" => Op::Prod,
-            token => parse_op(token),
-        }
+fn parse_op(token: &Token) -> Result<Op, String> {
+    match &token.data {
+        TokenData::Op(op) => match op.as_str() {
+            "*" => Ok(Op::Mul),
+            "**" => Ok(Op::Double),
+            "+" => Ok(Op::Add),
+            "/" => Ok(Op::Div),
+            "-" => Ok(Op::Sub),
+            "!" => Ok(Op::Fact),
+            "^" => Ok(Op::Exp),
+            "^^" => Ok(Op::Square),
+            "<" => Ok(Op::Lt),
+            ">" => Ok(Op::Gt),
+            "==" => Ok(Op::Eq),
+            "!=" => Ok(Op::Ne),
+            ":" => Ok(Op::DefStart),
+            ";" => Ok(Op::DefEnd),
+            "c" => Ok(Op::Clear),
+            "inv" => Ok(Op::Inv),
+            "swap" => Ok(Op::Swap),
+            "sum" => Ok(Op::Sum),
+            "prod" => Ok(Op::Prod),
+            op => Err(format!("unknown operator `{}`", op)),
+        },
+        TokenData::Number(number) => value::parse(number)
+            .map(Op::Push)
+            .ok_or_else(|| format!("invalid number `{}`", number)),
+        TokenData::VarInit(name) => Ok(Op::VarInit(name.clone())),
+        TokenData::VarRef(name) => Ok(Op::VarRef(name.clone())),
+        TokenData::Unknown(token) => Err(format!("unknown token `{}`", token)),
     }
 }
 
-fn parse_op(token: &str) -> Op {
-    parse_var_init(token)
-        .or_else(|| parse_var_ref(token))
-        .or_else(|| parse_push(token))
-        .unwrap_or(Op::Noop)
-}
-
-fn parse_push(token: &str) -> Option<Op> {
-    isize::from_str(token).ok().map(Op::Push)
-}
-
-lazy_static! {
-    static ref INIT_RE: Regex = Regex::new(r"=([a-zA-Z][a-zA-Z0-9]*)").unwrap();
-}
-
-fn parse_var_init(token: &str) -> Option<Op> {
-    INIT_RE
-        .captures(token)
-        .and_then(|captures| captures.get(1))
-        .map(|re_match| Op::VarInit(re_match.as_str().to_string()))
+/// Reads the next token as a bare word name, for combinators like `if`
+/// and `while` that take a quoted word rather than executing it.
+fn read_word_name(tokens: &mut impl Iterator<Item = Token>, combinator: &str) -> Result<String, String> {
+    match tokens.next().map(|token| token.data) {
+        Some(TokenData::Unknown(name)) => Ok(name),
+        _ => Err(format!("expected a word name after `{}`", combinator)),
+    }
 }
 
-lazy_static! {
-    static ref VAR_RE: Regex = Regex::new(r"\$([a-zA-Z][a-zA-Z0-9]*)").unwrap();
+fn bool_value(b: bool) -> Value {
+    Value::Int(if b { 1 } else { 0 })
 }
 
-fn parse_var_ref(token: &str) -> Option<Op> {
-    VAR_RE
-        .captures(token)
-        .and_then(|captures| captures.get(1))
-        .map(|re_match| Op::VarRef(re_match.as_str().to_string()))
+fn underflow1() -> String {
+    "stack underflow: expected 1 operand".to_string()
 }
 
-fn save_div(a: isize, b: isize) -> Option<isize> {
-    if b == 0 {
-        None
-    } else {
-        Some(a / b)
-    }
+fn underflow2() -> String {
+    "stack underflow: expected 2 operands".to_string()
 }
 
 #[cfg(test)]
@@ -273,33 +435,149 @@ mod test {
     fn exec() {
         let mut state = State::new();
 
-        state.exec(Op::Push(3));
-        state.exec(Op::Push(5));
-        state.exec(Op::Add);
-        assert_eq!(state.peek(), Some(&8));
+        state.exec(Op::Push(Value::Int(3))).unwrap();
+        state.exec(Op::Push(Value::Int(5))).unwrap();
+        state.exec(Op::Add).unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(8)));
     }
 
     #[test]
     fn eval() {
         let mut state = State::new();
 
-        state.eval("3 5 +");
-        assert_eq!(state.peek(), Some(&8));
+        state.eval("3 5 +").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(8)));
+    }
+
+    #[test]
+    fn eval_floats_and_division() {
+        let mut state = State::new();
+
+        state.eval("1.0 2 /").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Float(0.5)));
+    }
+
+    #[test]
+    fn eval_complex() {
+        let mut state = State::new();
+
+        state.eval("3+4i 1+1i +").unwrap();
+        assert_eq!(
+            state.peek(),
+            Some(&Value::Complex(num_complex::Complex64::new(4.0, 5.0)))
+        );
     }
 
     #[test]
     fn variables() {
         let mut state = State::new();
 
-        state.eval("3 =foo");
+        state.eval("3 =foo").unwrap();
         assert_eq!(state.peek(), None);
-        state.eval("$foo");
-        assert_eq!(state.peek(), Some(&3));
+        state.eval("$foo").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(3)));
+    }
+
+    #[test]
+    fn eval_unknown_token() {
+        let mut state = State::new();
+
+        let diags = state.eval("3 wat").unwrap_err();
+        assert_eq!(diags[0].message, "unknown token `wat`");
+        assert_eq!(diags[0].span, lex::Span::new(2, 5));
     }
 
     #[test]
-    fn test_save_div() {
-        assert_eq!(save_div(24, 2), Some(12));
-        assert_eq!(save_div(24, 0), None);
+    fn eval_stack_underflow() {
+        let mut state = State::new();
+
+        let diags = state.eval("+").unwrap_err();
+        assert_eq!(diags[0].message, "stack underflow: expected 2 operands");
+    }
+
+    #[test]
+    fn eval_division_by_zero() {
+        let mut state = State::new();
+
+        let diags = state.eval("1 0 /").unwrap_err();
+        assert_eq!(diags[0].message, "division by zero");
+    }
+
+    #[test]
+    fn eval_word_definition_and_call() {
+        let mut state = State::new();
+
+        state.eval(": hypot ^^ swap ^^ + ;").unwrap();
+        state.eval("3 4 hypot").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(25)));
+    }
+
+    #[test]
+    fn eval_word_recursion_guard() {
+        let mut state = State::new();
+
+        state.eval(": loop loop ;").unwrap();
+        let diags = state.eval("loop").unwrap_err();
+        assert_eq!(
+            diags[0].message,
+            "recursion limit exceeded calling `loop`"
+        );
+    }
+
+    #[test]
+    fn eval_comparisons() {
+        let mut state = State::new();
+
+        state.eval("3 5 <").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(1)));
+        state.eval("c 3 5 >").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(0)));
+        state.eval("c 5 5 ==").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(1)));
+        state.eval("c 5 5 !=").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(0)));
+    }
+
+    #[test]
+    fn eval_if_combinator() {
+        let mut state = State::new();
+
+        state.eval(": answer 42 ;").unwrap();
+        state.eval("1 if answer").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(42)));
+
+        state.eval("c 0 if answer").unwrap();
+        assert_eq!(state.peek(), None);
+    }
+
+    #[test]
+    fn eval_while_combinator() {
+        let mut state = State::new();
+
+        state.eval("0 =n").unwrap();
+        state.eval(": cond $n 3 < ;").unwrap();
+        state.eval(": incr $n 1 + =n ;").unwrap();
+        state.eval("while cond incr").unwrap();
+        state.eval("$n").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(3)));
+    }
+
+    #[test]
+    fn eval_combinators_inside_word_definition() {
+        let mut state = State::new();
+
+        state.eval(": cond $n 3 < ;").unwrap();
+        state.eval(": incr $n 1 + =n ;").unwrap();
+        state.eval(": answer 42 ;").unwrap();
+
+        state.eval(": count_up 0 =n while cond incr ;").unwrap();
+        state.eval(": maybe_answer 1 if answer ;").unwrap();
+
+        state.eval("count_up").unwrap();
+        state.eval("$n").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(3)));
+
+        state.eval("maybe_answer").unwrap();
+        assert_eq!(state.peek(), Some(&Value::Int(42)));
     }
 }
@@ -0,0 +1,233 @@
+use num_complex::Complex64;
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+
+/// A stack value. Arithmetic between two `Value`s promotes the narrower
+/// operand: `Int` + `Int` stays `Int`, anything mixed with `Float`
+/// promotes to `Float`, and anything with an imaginary part promotes to
+/// `Complex`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i128),
+    Float(f64),
+    Complex(Complex64),
+}
+
+impl Value {
+    fn to_float(&self) -> f64 {
+        match self {
+            Value::Int(n) => *n as f64,
+            Value::Float(n) => *n,
+            Value::Complex(c) => c.re,
+        }
+    }
+
+    fn to_complex(&self) -> Complex64 {
+        match self {
+            Value::Int(n) => Complex64::new(*n as f64, 0.0),
+            Value::Float(n) => Complex64::new(*n, 0.0),
+            Value::Complex(c) => *c,
+        }
+    }
+}
+
+fn promote(a: Value, b: Value) -> (Value, Value) {
+    match (&a, &b) {
+        (Value::Complex(_), _) | (_, Value::Complex(_)) => {
+            (Value::Complex(a.to_complex()), Value::Complex(b.to_complex()))
+        }
+        (Value::Float(_), _) | (_, Value::Float(_)) => {
+            (Value::Float(a.to_float()), Value::Float(b.to_float()))
+        }
+        _ => (a, b),
+    }
+}
+
+pub fn add(a: Value, b: Value) -> Value {
+    match promote(a, b) {
+        (Value::Int(a), Value::Int(b)) => Value::Int(a + b),
+        (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
+        (Value::Complex(a), Value::Complex(b)) => Value::Complex(a + b),
+        _ => unreachable!("promote always yields matching variants"),
+    }
+}
+
+pub fn sub(a: Value, b: Value) -> Value {
+    match promote(a, b) {
+        (Value::Int(a), Value::Int(b)) => Value::Int(a - b),
+        (Value::Float(a), Value::Float(b)) => Value::Float(a - b),
+        (Value::Complex(a), Value::Complex(b)) => Value::Complex(a - b),
+        _ => unreachable!("promote always yields matching variants"),
+    }
+}
+
+pub fn mul(a: Value, b: Value) -> Value {
+    match promote(a, b) {
+        (Value::Int(a), Value::Int(b)) => Value::Int(a * b),
+        (Value::Float(a), Value::Float(b)) => Value::Float(a * b),
+        (Value::Complex(a), Value::Complex(b)) => Value::Complex(a * b),
+        _ => unreachable!("promote always yields matching variants"),
+    }
+}
+
+/// Integer division when both operands are `Int`, true division
+/// otherwise.
+pub fn div(dividend: Value, divisor: Value) -> Result<Value, String> {
+    match (dividend, divisor) {
+        (Value::Int(a), Value::Int(b)) => {
+            if b == 0 {
+                Err("division by zero".to_string())
+            } else {
+                Ok(Value::Int(a / b))
+            }
+        }
+        (a, b) => match promote(a, b) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a / b)),
+            (Value::Complex(a), Value::Complex(b)) => Ok(Value::Complex(a / b)),
+            _ => unreachable!("promote always yields matching variants"),
+        },
+    }
+}
+
+pub fn pow(base: Value, exp: Value) -> Value {
+    if let (Value::Int(base), Value::Int(exp)) = (&base, &exp) {
+        if *exp >= 0 {
+            return Value::Int(base.pow(*exp as u32));
+        }
+    }
+
+    match promote(base, exp) {
+        (Value::Int(base), Value::Int(exp)) => Value::Float((base as f64).powf(exp as f64)),
+        (Value::Float(base), Value::Float(exp)) => Value::Float(base.powf(exp)),
+        (Value::Complex(base), Value::Complex(exp)) => Value::Complex(base.powc(exp)),
+        _ => unreachable!("promote always yields matching variants"),
+    }
+}
+
+pub fn neg(a: Value) -> Value {
+    match a {
+        Value::Int(a) => Value::Int(-a),
+        Value::Float(a) => Value::Float(-a),
+        Value::Complex(a) => Value::Complex(-a),
+    }
+}
+
+pub fn square(a: Value) -> Value {
+    mul(a.clone(), a)
+}
+
+/// Orders two values, promoting as arithmetic does. Complex values have
+/// no natural ordering and are rejected.
+pub fn compare(a: Value, b: Value) -> Result<Ordering, String> {
+    match promote(a, b) {
+        (Value::Int(a), Value::Int(b)) => Ok(a.cmp(&b)),
+        (Value::Float(a), Value::Float(b)) => {
+            a.partial_cmp(&b).ok_or_else(|| "cannot compare NaN".to_string())
+        }
+        (Value::Complex(_), Value::Complex(_)) => {
+            Err("cannot compare complex numbers".to_string())
+        }
+        _ => unreachable!("promote always yields matching variants"),
+    }
+}
+
+pub fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Int(n) => *n != 0,
+        Value::Float(n) => *n != 0.0,
+        Value::Complex(c) => *c != Complex64::new(0.0, 0.0),
+    }
+}
+
+pub fn factorial(a: Value) -> Result<Value, String> {
+    match a {
+        Value::Int(n) if n >= 0 => Ok(Value::Int((1..=n).product())),
+        Value::Int(_) => Err("factorial of a negative number".to_string()),
+        _ => Err("factorial requires an integer".to_string()),
+    }
+}
+
+/// Parses an `i128`, then an `f64`, then an `a+bi`/`a-bi`/`bi` complex
+/// literal.
+pub fn parse(text: &str) -> Option<Value> {
+    text.parse::<i128>()
+        .ok()
+        .map(Value::Int)
+        .or_else(|| text.parse::<f64>().ok().map(Value::Float))
+        .or_else(|| parse_complex(text).map(Value::Complex))
+}
+
+fn parse_complex(text: &str) -> Option<Complex64> {
+    let body = text.strip_suffix('i')?;
+
+    let split = body
+        .char_indices()
+        .collect::<Vec<_>>()
+        .into_iter()
+        .skip(1)
+        .rev()
+        .find(|&(_, c)| c == '+' || c == '-');
+
+    match split {
+        Some((idx, _)) => {
+            let re = body[..idx].parse::<f64>().ok()?;
+            let im = match &body[idx..] {
+                "+" => 1.0,
+                "-" => -1.0,
+                im => im.parse::<f64>().ok()?,
+            };
+            Some(Complex64::new(re, im))
+        }
+        None if body.is_empty() => Some(Complex64::new(0.0, 1.0)),
+        None => body.parse::<f64>().ok().map(|im| Complex64::new(0.0, im)),
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Complex(c) if c.im == 0.0 => write!(f, "{}", c.re),
+            Value::Complex(c) if c.re == 0.0 => write!(f, "{}i", c.im),
+            Value::Complex(c) if c.im < 0.0 => write!(f, "{}{}i", c.re, c.im),
+            Value::Complex(c) => write!(f, "{}+{}i", c.re, c.im),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn add_promotes() {
+        assert_eq!(add(Value::Int(1), Value::Int(2)), Value::Int(3));
+        assert_eq!(add(Value::Int(1), Value::Float(2.5)), Value::Float(3.5));
+    }
+
+    #[test]
+    fn div_is_type_aware() {
+        assert_eq!(div(Value::Int(7), Value::Int(2)), Ok(Value::Int(3)));
+        assert_eq!(
+            div(Value::Float(7.0), Value::Int(2)),
+            Ok(Value::Float(3.5))
+        );
+        assert_eq!(
+            div(Value::Int(1), Value::Int(0)),
+            Err("division by zero".to_string())
+        );
+    }
+
+    #[test]
+    fn pow_with_negative_exponent_promotes_to_float() {
+        assert_eq!(pow(Value::Int(2), Value::Int(-1)), Value::Float(0.5));
+    }
+
+    #[test]
+    fn parse_complex_literal() {
+        assert_eq!(parse("3+4i"), Some(Value::Complex(Complex64::new(3.0, 4.0))));
+        assert_eq!(parse("-4i"), Some(Value::Complex(Complex64::new(0.0, -4.0))));
+        assert_eq!(parse("2-1i"), Some(Value::Complex(Complex64::new(2.0, -1.0))));
+    }
+}
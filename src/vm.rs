@@ -0,0 +1,112 @@
+use std::cmp::Ordering;
+
+use super::compiler::{BinKind, DrainKind, Instr, UnKind};
+use super::value::{self, Value};
+use super::{underflow1, underflow2, State};
+
+static MAX_CALL_DEPTH: usize = 64;
+
+/// Runs a compiled instruction stream against `state`'s stack, vars,
+/// and defined words.
+pub fn run(state: &mut State, code: &[Instr]) -> Result<(), String> {
+    let mut ip = 0;
+
+    while ip < code.len() {
+        match &code[ip] {
+            Instr::NumPush(value) => {
+                state.push(value.clone());
+            }
+            Instr::Get(name) => {
+                if let Some(value) = state.get_var(name) {
+                    state.push(value);
+                }
+            }
+            Instr::Set(name) => {
+                let value = state.stack.pop().ok_or_else(underflow1)?;
+                state.add_var(name.clone(), value);
+            }
+            Instr::BinOp(kind) => bin_op(state, *kind)?,
+            Instr::UnOp(kind) => un_op(state, *kind)?,
+            Instr::Drain(kind) => drain(state, *kind),
+            Instr::Clear => state.clear(),
+            Instr::Swap => {
+                let (a, b) = state.pop2().ok_or_else(underflow2)?;
+                state.push(a).push(b);
+            }
+            Instr::CallWord(name) => call(state, name)?,
+            Instr::Jump(target) => {
+                ip = *target;
+                continue;
+            }
+            Instr::JumpIfZero(target) => {
+                let cond = state.stack.pop().ok_or_else(underflow1)?;
+                if !value::is_truthy(&cond) {
+                    ip = *target;
+                    continue;
+                }
+            }
+        }
+
+        ip += 1;
+    }
+
+    Ok(())
+}
+
+fn bin_op(state: &mut State, kind: BinKind) -> Result<(), String> {
+    match kind {
+        BinKind::Add => state.apply2(value::add),
+        BinKind::Sub => state.apply2(|a, b| value::sub(b, a)),
+        BinKind::Mul => state.apply2(value::mul),
+        BinKind::Div => {
+            let (a, b) = state.pop2().ok_or_else(underflow2)?;
+            let result = value::div(b, a)?;
+            state.push(result);
+            Ok(())
+        }
+        BinKind::Exp => state.apply2(|a, b| value::pow(b, a)),
+        BinKind::Lt => state.compare(|ord| ord == Ordering::Less),
+        BinKind::Gt => state.compare(|ord| ord == Ordering::Greater),
+        BinKind::Eq => state.compare(|ord| ord == Ordering::Equal),
+        BinKind::Ne => state.compare(|ord| ord != Ordering::Equal),
+    }
+}
+
+fn un_op(state: &mut State, kind: UnKind) -> Result<(), String> {
+    match kind {
+        UnKind::Double => state.apply(|a| value::mul(a, Value::Int(2))),
+        UnKind::Square => state.apply(value::square),
+        UnKind::Inv => state.apply(value::neg),
+        UnKind::Fact => state.apply_try(value::factorial),
+    }
+}
+
+fn drain(state: &mut State, kind: DrainKind) {
+    match kind {
+        DrainKind::Sum => {
+            let sum = state.drain().fold(Value::Int(0), value::add);
+            state.push(sum);
+        }
+        DrainKind::Prod => {
+            let product = state.drain().fold(Value::Int(1), value::mul);
+            state.push(product);
+        }
+    }
+}
+
+fn call(state: &mut State, name: &str) -> Result<(), String> {
+    if state.call_depth >= MAX_CALL_DEPTH {
+        return Err(format!("recursion limit exceeded calling `{}`", name));
+    }
+
+    let body = state
+        .words
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("undefined word `{}`", name))?;
+
+    state.call_depth += 1;
+    let result = run(state, &body);
+    state.call_depth -= 1;
+    result
+}